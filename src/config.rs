@@ -2,9 +2,10 @@ use prelude::*;
 use std::fs::{File};
 use std::path::Path;
 use serialport::{self,SerialPortType,UsbPortInfo};
+use log::LogLevel;
 use json;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct KeyMap {
     ///The device pin to map this key to.
     pub pin: u8,
@@ -20,9 +21,25 @@ pub enum DebounceType {
     LastChange,
 }
 
-#[derive(Serialize, Deserialize)]
+///Which auto-reset dance, if any, to perform on the control lines right
+///after opening the serial port, to reboot the device into a known state
+///before the handshake.
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResetSequence {
+    ///Don't touch the control lines.
+    None,
+    ///The classic Arduino auto-reset: pulse DTR low then high.
+    Arduino,
+    ///The DTR/RTS two-line dance used by esptool/espflash to reset into
+    ///bootloader mode.
+    Esp,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     ///What serial port to use to connect to the device.
+    ///A value of the form `tcp://host:port` connects over TCP instead,
+    ///for devices bridged through a ser2net-style gateway.
     pub serial_port: String,
     ///A command-line command to run before proceeding to attempt the connection.
     ///Used to program the device with a suitable server before connecting.
@@ -40,8 +57,25 @@ pub struct Config {
     pub enable_interrupts: bool,
     ///How long to wait for the device to respond.
     pub timeout_ms: u64,
-    ///Print all sorts of stuff.
-    pub verbose: bool,
+    ///Skip the descriptor probe and assume a legacy (protocol version 0)
+    ///device, instead of waiting out a full `timeout_ms` on every connect
+    ///and reconnect to find out the device isn't going to send one. Worth
+    ///setting for any device known not to speak the descriptor protocol.
+    pub assume_legacy_device: bool,
+    ///Whether to automatically reconnect if the device disconnects, instead
+    ///of treating it as a fatal error.
+    pub reconnect: bool,
+    ///How long to wait between reconnect attempts.
+    pub reconnect_backoff_ms: u64,
+    ///What auto-reset sequence to pulse on the DTR/RTS control lines right
+    ///after opening the port, before the handshake.
+    pub reset_sequence: ResetSequence,
+    ///How long to hold each control line state during the reset sequence.
+    pub reset_delay_ms: u64,
+    ///How verbose the program's logging should be.
+    pub log_level: LogLevel,
+    ///Whether to prefix log lines with a millisecond timestamp.
+    pub log_timestamps: bool,
 }
 impl Default for Config {
     fn default() -> Config {
@@ -58,7 +92,13 @@ impl Default for Config {
             debounce_type: DebounceType::LastChange,
             enable_interrupts: false,
             timeout_ms: 3000,
-            verbose: false,
+            assume_legacy_device: false,
+            reconnect: true,
+            reconnect_backoff_ms: 2000,
+            reset_sequence: ResetSequence::None,
+            reset_delay_ms: 100,
+            log_level: LogLevel::Info,
+            log_timestamps: false,
         }
     }
 }
@@ -66,10 +106,6 @@ impl Config {
     ///Load or create a config file.
     ///Never errors, as it will use a default if missing.
     pub fn create<P: AsRef<Path>>(path: P) -> Config {
-        //Write configuration file (delayed)
-        let write_cfg =
-            |cfg: &Config| -> Result<()> { Ok(json::to_writer_pretty(File::create(&path)?, cfg)?) };
-
         //Read configuration
         let cfg = || -> Result<_> { Ok(json::from_reader(File::open(&path)?)?) };
         match cfg() {
@@ -78,14 +114,19 @@ impl Config {
                 eprintln!("error reading config file: {}", err);
                 eprintln!("using default config");
                 let cfg = Config::default();
-                if let Err(err) = write_cfg(&cfg) {
+                if let Err(err) = cfg.save(&path) {
                     eprintln!("error writing config file: {}", err);
                 }
                 cfg
             }
         }
     }
-    
+
+    ///Write this config out as pretty-printed JSON.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Ok(json::to_writer_pretty(File::create(path)?, self)?)
+    }
+
     ///Get a physical port name, resolving any wildcards in the config.
     pub fn resolve_port(&self)->Result<String> {
         Ok(match &*self.serial_port {
@@ -1,12 +1,93 @@
 use serialport::{self,SerialPort,SerialPortType,SerialPortSettings};
 use prelude::*;
-use config::{Config,DebounceType};
+use config::{Config,DebounceType,ResetSequence};
 use event::{Event};
+use transport::{Transport,TcpTransport};
+use enigo::{self,Enigo,KeyboardControllable};
+use std::error::Error;
 use std::time::Duration;
+use std::thread;
+use std::io;
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,RecvTimeoutError};
 
 
 const MAGIC_NUMBER: &[u8] = b"SerKey01";
 
+///An error that no amount of retrying will fix: a config/device mismatch
+///(bad pin, unsupported protocol version) rather than a transient I/O
+///hiccup. The reconnect loop in `reader_thread` gives up on these instead
+///of retrying forever with backoff.
+#[derive(Debug)]
+struct FatalError(String);
+impl fmt::Display for FatalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Error for FatalError {}
+
+///Walk an error's cause chain looking for a `FatalError`, so a fatal error
+///wrapped by `.chain(...)` (as `BoxErrorMsg`) is still recognized as fatal.
+///Uses `source()` rather than the deprecated `cause()`, since only
+///`source()`'s `'static` bound lets `downcast_ref` see past the first hop.
+fn is_fatal(err: &(Error + 'static)) -> bool {
+    if err.downcast_ref::<FatalError>().is_some() {
+        return true;
+    }
+    match err.source() {
+        Some(cause) => is_fatal(cause),
+        None => false,
+    }
+}
+
+///The descriptor protocol version this client understands. A device
+///reporting a higher version, or a non-zero version we don't recognize, is
+///rejected rather than risking sending it commands it doesn't understand.
+const PROTOCOL_VERSION: u8 = 1;
+
+const FEATURE_INTERRUPTS: u8 = 0x01;
+const FEATURE_DEBOUNCE: u8 = 0x02;
+//Bit 0x04 (per-key debounce) is reserved: no firmware or config option uses
+//it yet, so there's nothing to validate against it. Give it a decoder once
+//a config knob for it exists, rather than keeping an accessor no caller
+//reads.
+
+///Capabilities reported by the device right after the magic number, so the
+///client can validate the config against what the firmware actually
+///supports instead of blindly sending commands it might ignore.
+#[derive(Copy, Clone, Debug)]
+pub struct DeviceInfo {
+    ///The descriptor protocol version the device speaks. 0 means a legacy
+    ///device that sends no descriptor at all.
+    pub protocol_version: u8,
+    ///How many pins the device exposes.
+    pub pin_count: u8,
+    ///Maximum number of keys the device can track at once. A config with
+    ///more key maps than this is rejected during the handshake.
+    pub max_keys: u8,
+    features: u8,
+}
+impl DeviceInfo {
+    ///Assumed capabilities of a legacy device that sends no descriptor.
+    fn legacy() -> DeviceInfo {
+        DeviceInfo {
+            protocol_version: 0,
+            pin_count: u8::max_value(),
+            max_keys: u8::max_value(),
+            features: FEATURE_INTERRUPTS | FEATURE_DEBOUNCE,
+        }
+    }
+
+    pub fn supports_interrupts(&self) -> bool {
+        self.features & FEATURE_INTERRUPTS != 0
+    }
+    pub fn supports_debounce(&self) -> bool {
+        self.features & FEATURE_DEBOUNCE != 0
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 enum SetupCommand {
     Finish,
@@ -30,36 +111,108 @@ impl SetupCommand {
     }
 }
 
+///A live connection to the device, backed by a background thread that reads
+///events off the transport and, should the device disconnect, transparently
+///reconnects.
 pub struct Connection {
-    serial: Box<SerialPort>,
+    events: Receiver<Event>,
+    connected: Arc<AtomicBool>,
+    device_info: Arc<Mutex<DeviceInfo>>,
 }
 impl Connection {
     pub fn open(cfg: &Config) -> Result<Connection> {
+        //Connect synchronously once, so a bad config is reported immediately
+        //instead of retrying forever in the background.
+        let (transport, info) = open_transport(cfg).chain("failed to open connection")?;
+
+        let (tx, rx) = mpsc::channel();
+        let connected = Arc::new(AtomicBool::new(true));
+        let device_info = Arc::new(Mutex::new(info));
+        let cfg = cfg.clone();
+        let thread_connected = connected.clone();
+        let thread_device_info = device_info.clone();
+        thread::spawn(move || reader_thread(cfg, transport, tx, thread_connected, thread_device_info));
+
+        Ok(Connection {
+            events: rx,
+            connected,
+            device_info,
+        })
+    }
+
+    ///Whether the device is currently connected (false while a reconnect is
+    ///in progress).
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    ///The capabilities the device reported during the last handshake.
+    pub fn device_info(&self) -> DeviceInfo {
+        *self.device_info.lock().unwrap()
+    }
+
+    ///Block until an event is read from the device, transparently surviving
+    ///any reconnects performed by the reader thread.
+    pub fn read_event(&mut self, _cfg: &Config) -> Result<Event> {
+        self.events.recv().chain("reader thread has shut down")
+    }
+
+    ///Wait up to `timeout` for an event, returning `None` if none arrives in
+    ///time instead of blocking forever. Used by the monitor mode, which
+    ///needs to interleave device events with commands typed by the user.
+    pub fn read_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err("reader thread has shut down".into()),
+        }
+    }
+}
+
+///If `serial_port` names a `tcp://host:port` bridge, return the
+///`host:port` address to connect to.
+fn parse_tcp_addr(serial_port: &str) -> Option<&str> {
+    if serial_port.starts_with("tcp://") {
+        Some(&serial_port["tcp://".len()..])
+    } else {
+        None
+    }
+}
+
+///Open the transport and run it through the magic-number handshake.
+fn open_transport(cfg: &Config) -> Result<(Box<Transport>, DeviceInfo)> {
+    //A `tcp://host:port` serial port name means the device is bridged over
+    //TCP (e.g. through a ser2net-style gateway) instead of being a local
+    //serial port.
+    let mut transport: Box<Transport> = if let Some(addr) = parse_tcp_addr(&cfg.serial_port) {
+        log_info!("connecting to tcp device at '{}'", addr);
+        Box::new(TcpTransport::connect(addr, cfg.timeout_ms)
+            .chain("failed to connect to tcp device, ensure the gateway is reachable")?)
+    } else {
         //Print available ports
-        println!("available ports:");
+        log_debug!("available ports:");
         for port in serialport::available_ports().chain("failed to enumerate available ports")? {
-            print!(" {}: ", port.port_name);
             match port.port_type {
                 SerialPortType::UsbPort(info) => {
-                    println!("usb port");
-                    println!("  vendor id: 0x{:X}", info.vid);
-                    println!("  product id: 0x{:X}", info.pid);
-                    println!(
+                    log_debug!(" {}: usb port", port.port_name);
+                    log_debug!("  vendor id: 0x{:X}", info.vid);
+                    log_debug!("  product id: 0x{:X}", info.pid);
+                    log_debug!(
                         "  serial number: '{}'",
                         info.serial_number.unwrap_or("unavailable".into())
                     );
-                    println!(
+                    log_debug!(
                         "  manufacturer: '{}'",
                         info.manufacturer.unwrap_or("unavailable".into())
                     );
-                    println!(
+                    log_debug!(
                         "  product name: '{}'",
                         info.product.unwrap_or("unavailable".into())
                     );
                 }
-                SerialPortType::PciPort => println!("pci port"),
-                SerialPortType::BluetoothPort => println!("bluetooth port"),
-                SerialPortType::Unknown => println!("unknown port type"),
+                SerialPortType::PciPort => log_debug!(" {}: pci port", port.port_name),
+                SerialPortType::BluetoothPort => log_debug!(" {}: bluetooth port", port.port_name),
+                SerialPortType::Unknown => log_debug!(" {}: unknown port type", port.port_name),
             }
         }
 
@@ -67,8 +220,8 @@ impl Connection {
         let portname = cfg.resolve_port()?;
 
         //Open port
-        println!("opening serial port '{}'", portname);
-        let serial = serialport::open_with_settings(
+        log_info!("opening serial port '{}'", portname);
+        let mut serial = serialport::open_with_settings(
             &portname,
             &SerialPortSettings {
                 baud_rate: cfg.baud_rate,
@@ -77,63 +230,174 @@ impl Connection {
             },
         ).chain("failed to open serial port, ensure device is connected and the correct port is being used")?;
 
-        //Create and init connection
-        let mut conn = Connection { serial };
-        conn.initialize(cfg)
-            .chain("failed to initialize connection")?;
-        Ok(conn)
-    }
-
-    fn read_magic(&mut self, cfg: &Config) -> Result<()> {
-        let mut magic_idx = 0;
-        let mut garbage = 0;
-        if cfg.verbose {
-            print!("reading magic number: '");
-        }
-        while magic_idx < MAGIC_NUMBER.len() {
-            let mut byte = [0; 1];
-            self.serial
-                .read(&mut byte)
-                .chain("reading magic number failed")?;
-            let byte = byte[0];
-            if cfg.verbose {
-                print!("{}", byte as char);
-            }
-            if byte == MAGIC_NUMBER[magic_idx] {
-                magic_idx += 1;
-            } else {
-                garbage += magic_idx + 1;
-                magic_idx = 0;
-            }
+        perform_reset_sequence(&mut *serial, cfg)
+            .chain("failed to perform device reset sequence")?;
+        Box::new(serial)
+    };
+
+    let info = initialize(&mut *transport, cfg).chain("failed to initialize connection")?;
+    Ok((transport, info))
+}
+
+///Toggle the DTR/RTS control lines to reboot the device into a known state,
+///right after opening the port and before the handshake. Mirrors the
+///classic Arduino auto-reset and the reset dance espflash performs before
+///connecting, so a `previous_command` isn't needed just to reboot the board.
+fn perform_reset_sequence(serial: &mut SerialPort, cfg: &Config) -> Result<()> {
+    let delay = Duration::from_millis(cfg.reset_delay_ms);
+    match cfg.reset_sequence {
+        ResetSequence::None => {}
+        ResetSequence::Arduino => {
+            serial.write_data_terminal_ready(false)?;
+            thread::sleep(delay);
+            serial.write_data_terminal_ready(true)?;
+        }
+        ResetSequence::Esp => {
+            serial.write_data_terminal_ready(false)?;
+            serial.write_request_to_send(true)?;
+            thread::sleep(delay);
+            serial.write_data_terminal_ready(true)?;
+            serial.write_request_to_send(false)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_magic(transport: &mut Transport, _cfg: &Config) -> Result<()> {
+    let mut magic_idx = 0;
+    let mut garbage = 0;
+    let mut raw = String::new();
+    while magic_idx < MAGIC_NUMBER.len() {
+        let mut byte = [0; 1];
+        let n = transport
+            .read(&mut byte)
+            .chain("reading magic number failed")?;
+        if n == 0 {
+            //A blocking serial port reports a closed device as a read
+            //timeout, but `TcpTransport` reports it as an orderly `Ok(0)`
+            //(peer closed its end), e.g. a ser2net gateway dropping the
+            //link. Without this check that reads as a garbage byte and
+            //the loop spins forever instead of erroring out.
+            return Err("device closed the connection while sending the magic number".into());
         }
-        if cfg.verbose {
-            println!("'");
+        let byte = byte[0];
+        raw.push(byte as char);
+        if byte == MAGIC_NUMBER[magic_idx] {
+            magic_idx += 1;
+        } else {
+            garbage += magic_idx + 1;
+            magic_idx = 0;
         }
-        println!("received magic number after {} bytes of garbage", garbage);
-        Ok(())
     }
+    log_trace!("read magic number: '{}'", raw);
+    log_info!("received magic number after {} bytes of garbage", garbage);
+    Ok(())
+}
+
+///Read the 4-byte descriptor a device sends immediately after the magic
+///number (protocol version, pin count, feature bitmask, max keys). A
+///legacy device (pre-dating this protocol) sends nothing at all, which is
+///detected as a read timeout and treated as version 0 with every legacy
+///assumption held.
+///
+///That detection costs a full `timeout_ms` stall (3s by default) on every
+///connect, since there's no way to tell "no descriptor coming" from "device
+///is just slow" except waiting out the timeout. For a legacy device --
+///today, the common case, since no v1 firmware exists yet -- set
+///`cfg.assume_legacy_device` to skip the probe and avoid paying that cost
+///on every connect and reconnect.
+fn negotiate_descriptor(transport: &mut Transport, cfg: &Config) -> Result<DeviceInfo> {
+    if cfg.assume_legacy_device {
+        log_info!("assume_legacy_device is set, skipping the descriptor probe");
+        return Ok(DeviceInfo::legacy());
+    }
+
+    transport.set_timeout(Duration::from_millis(cfg.timeout_ms))?;
+
+    //Read the descriptor byte by byte so a timeout can be told apart from a
+    //legacy device: no bytes at all before the first timeout means legacy,
+    //but some bytes followed by a timeout means a v1 device that stalled
+    //partway through, which must not be silently treated as legacy (the
+    //bytes it already sent would desync every read that follows).
+    let mut descriptor = [0u8; 4];
+    let mut read = 0;
+    while read < descriptor.len() {
+        match transport.read(&mut descriptor[read..]) {
+            Ok(n) if n > 0 => read += n,
+            Ok(_) => return Err("device closed the connection while sending its descriptor".into()),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock => {
+                if read == 0 {
+                    log_info!("device sent no descriptor, assuming legacy protocol version 0");
+                    return Ok(DeviceInfo::legacy());
+                } else {
+                    return Err(format!(
+                        "device sent only {} of {} descriptor bytes before timing out",
+                        read, descriptor.len()
+                    ).into());
+                }
+            }
+            Err(err) => return Err(err).chain("failed to read device descriptor"),
+        }
+    }
+
+    let info = DeviceInfo {
+        protocol_version: descriptor[0],
+        pin_count: descriptor[1],
+        features: descriptor[2],
+        max_keys: descriptor[3],
+    };
+    if info.protocol_version != 0 && info.protocol_version != PROTOCOL_VERSION {
+        //A reconnect will never make the device speak a version we
+        //understand, so mark this fatal rather than retrying forever.
+        return Err(Box::new(FatalError(format!(
+            "unsupported device protocol version {} (this client speaks version {}, or legacy 0)",
+            info.protocol_version, PROTOCOL_VERSION
+        ))));
+    }
+    Ok(info)
+}
+
+///Read the magic number, recognizing and opening the connection.
+fn initialize(transport: &mut Transport, cfg: &Config) -> Result<DeviceInfo> {
+    //Send a reboot message in case the client is already running
+    transport
+        .write_all(&[SetupCommand::Reset.code(), 0, 0])
+        .chain("failed to write reset command")?;
 
-    ///Read the magic number, recognizing and opening the connection.
-    fn initialize(&mut self, cfg: &Config) -> Result<()> {
-        //Send a reboot message in case the client is already running
-        self.serial
-            .write_all(&[SetupCommand::Reset.code(), 0, 0])
-            .chain("failed to write reset command")?;
+    //Send magic number
+    transport
+        .write_all(MAGIC_NUMBER)
+        .chain("failed to send magic number")?;
 
-        //Send magic number
-        self.serial
-            .write_all(MAGIC_NUMBER)
-            .chain("failed to send magic number")?;
+    //Receive magic number
+    read_magic(transport, cfg)?;
 
-        //Receive magic number
-        self.read_magic(cfg)?;
-        self.serial.set_timeout(Duration::from_millis(0))?;
+    //Negotiate capabilities and validate the config against them
+    let info = negotiate_descriptor(transport, cfg)?;
+    for keymap in cfg.key_maps.iter() {
+        if keymap.pin >= info.pin_count {
+            //A config error, not a device hiccup -- retrying won't fix it.
+            return Err(Box::new(FatalError(format!(
+                "key map uses pin {} but the device only exposes {} pins",
+                keymap.pin, info.pin_count
+            ))));
+        }
+    }
+    if cfg.key_maps.len() > info.max_keys as usize {
+        return Err(Box::new(FatalError(format!(
+            "config defines {} keys but the device can only track {} simultaneously",
+            cfg.key_maps.len(), info.max_keys
+        ))));
+    }
+    transport.set_timeout(Duration::from_millis(0))?;
 
-        //Set debounce length
+    //Set debounce, skipping the commands entirely if the device never told
+    //us it supports debounce at all
+    if info.supports_debounce() {
         let debounce = (cfg.debounce_ms * 1000.0)
             .min(u32::max_value() as f64)
             .max(0.0) as u32;
-        self.serial.write_all(&[
+        transport.write_all(&[
             SetupCommand::SetDebounce.code(),
             0,
             4,
@@ -142,76 +406,263 @@ impl Connection {
             ((debounce >> 8) & 0xFF) as u8,
             ((debounce >> 0) & 0xFF) as u8,
         ])?;
-        //Set debounce type
         match cfg.debounce_type {
             DebounceType::FirstChange => {
-                self.serial
+                transport
                     .write_all(&[SetupCommand::AwaitSmoothness.code(), 0, 1, 0])?;
             }
             DebounceType::LastChange => {
-                self.serial
+                transport
                     .write_all(&[SetupCommand::AwaitSmoothness.code(), 0, 1, 1])?;
             }
         }
-        //Setup keys
-        for keymap in cfg.key_maps.iter() {
-            self.serial
-                .write_all(&[SetupCommand::AddKey.code(), 0, 1, keymap.pin])
-                .chain("failed to setup key with device")?;
-        }
-        //Enable or disable interrupts
-        self.serial.write_all(&[
+    } else if cfg.debounce_ms > 0.0 {
+        log_warn!("device does not report debounce support, skipping debounce setup");
+    }
+    //Setup keys
+    for keymap in cfg.key_maps.iter() {
+        transport
+            .write_all(&[SetupCommand::AddKey.code(), 0, 1, keymap.pin])
+            .chain("failed to setup key with device")?;
+    }
+    //Enable or disable interrupts, skipping the command entirely if the
+    //device never told us it supports them
+    if info.supports_interrupts() {
+        transport.write_all(&[
             SetupCommand::EnableInterrupts.code(),
             0,
             1,
             if cfg.enable_interrupts { 1 } else { 0 },
         ])?;
-        //Send setup finish
-        self.serial
-            .write_all(&[SetupCommand::Finish.code(), 0, 0])
-            .chain("failed to finish setup")?;
-        
-        //Read setup output (until an empty line)
-        println!("device setup output:");
-        let mut line_buf = Vec::new();
+    } else if cfg.enable_interrupts {
+        log_warn!("device does not report interrupt support, skipping enable_interrupts");
+    }
+    //Send setup finish
+    transport
+        .write_all(&[SetupCommand::Finish.code(), 0, 0])
+        .chain("failed to finish setup")?;
+
+    //Read setup output (until an empty line)
+    log_debug!("device setup output:");
+    let mut line_buf = Vec::new();
+    loop {
+        line_buf.clear();
+        //Read all bytes until a newline
         loop {
-            line_buf.clear();
-            //Read all bytes until a newline
-            loop {
-                let mut char_buf = [0; 1];
-                self.serial
-                    .read_exact(&mut char_buf)
-                    .chain("failed to read setup log")?;
-                if &char_buf == b"\n" {
-                    break;
-                } else {
-                    line_buf.push(char_buf[0]);
-                }
-            }
-            //Quit if an empty line, otherwise print
-            let line = String::from_utf8_lossy(&line_buf);
-            let line = line.trim();
-            if line.is_empty() {
+            let mut char_buf = [0; 1];
+            transport
+                .read_exact(&mut char_buf)
+                .chain("failed to read setup log")?;
+            if &char_buf == b"\n" {
                 break;
             } else {
-                println!(" {}", line);
+                line_buf.push(char_buf[0]);
             }
         }
-        println!("--- setup finished ---");
+        //Quit if an empty line, otherwise print
+        let line = String::from_utf8_lossy(&line_buf);
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        } else {
+            log_debug!(" {}", line);
+        }
+    }
+    log_info!("--- setup finished ---");
+
+    //Set an infinite timeout
+    transport.set_timeout(Duration::from_millis(0))?;
+    //All ok
+    Ok(info)
+}
 
-        //Set an infinite timeout
-        self.serial.set_timeout(Duration::from_millis(0))?;
-        //All ok
-        Ok(())
+fn read_event(transport: &mut Transport, _cfg: &Config) -> Result<Event> {
+    let mut event = [0; 1];
+    transport.read_exact(&mut event)?;
+    log_trace!("received event byte 0x{:X}",event[0]);
+    Ok(Event::from_raw(event[0]))
+}
+
+///Release every currently held-down key, so a disconnect never leaves a key
+///stuck down on the host.
+fn release_keys(cfg: &Config, held: &[u8]) {
+    let mut enigo = Enigo::new();
+    for &idx in held {
+        if let Some(keymap) = cfg.key_maps.get(idx as usize) {
+            for &keycode in keymap.keycodes.iter() {
+                enigo.key_up(enigo::Key::Raw(keycode));
+            }
+        }
     }
+}
 
-    ///Block until an event is read.
-    pub fn read_event(&mut self,cfg: &Config) -> Result<Event> {
-        let mut event = [0; 1];
-        self.serial.read_exact(&mut event)?;
-        if cfg.verbose {
-            println!("received event byte 0x{:X}",event[0]);
+///Body of the background thread: reads events off `transport` and forwards
+///them through `tx`. On an I/O error or EOF, releases any held keys, flips
+///`connected` to false and retries `open_transport` with backoff until the
+///device reappears (unless `cfg.reconnect` is disabled) -- unless the
+///reconnect attempt fails with a `FatalError` (bad config, unsupported
+///device), in which case it gives up rather than looping forever in the
+///background.
+fn reader_thread(cfg: Config, mut transport: Box<Transport>, tx: mpsc::Sender<Event>, connected: Arc<AtomicBool>, device_info: Arc<Mutex<DeviceInfo>>) {
+    let mut held = Vec::new();
+    loop {
+        match read_event(&mut *transport, &cfg) {
+            Ok(event) => {
+                match event {
+                    Event::KeyDown(idx) => if !held.contains(&idx) {
+                        held.push(idx);
+                    },
+                    Event::KeyUp(idx) => held.retain(|&i| i != idx),
+                }
+                if tx.send(event).is_err() {
+                    //Consumer has gone away, nothing left to do
+                    return;
+                }
+            }
+            Err(err) => {
+                log_error!("lost connection to device: {}", err);
+                connected.store(false, Ordering::SeqCst);
+                release_keys(&cfg, &held);
+                held.clear();
+
+                if !cfg.reconnect {
+                    return;
+                }
+
+                //Retry opening the connection with backoff until it succeeds,
+                //unless the failure is one no amount of retrying will fix
+                //(e.g. a keymap pin the device doesn't have, or an
+                //unsupported protocol version), in which case give up
+                //instead of looping silently in the background forever.
+                loop {
+                    thread::sleep(Duration::from_millis(cfg.reconnect_backoff_ms));
+                    match open_transport(&cfg) {
+                        Ok((new_transport, info)) => {
+                            transport = new_transport;
+                            *device_info.lock().unwrap() = info;
+                            break;
+                        }
+                        Err(ref err) if is_fatal(&**err) => {
+                            log_error!("giving up reconnecting, unrecoverable error: {}", err);
+                            return;
+                        }
+                        Err(err) => {
+                            log_warn!("reconnect attempt failed: {}", err);
+                        }
+                    }
+                }
+                connected.store(true, Ordering::SeqCst);
+                log_info!("reconnected to device");
+            }
         }
-        Ok(Event::from_raw(event[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    ///A `Transport` that replays a scripted sequence of reads, for testing
+    ///the handshake logic without a real serial port or socket.
+    struct MockTransport {
+        reads: VecDeque<io::Result<Vec<u8>>>,
+    }
+    impl MockTransport {
+        fn new(reads: Vec<io::Result<Vec<u8>>>) -> MockTransport {
+            MockTransport {
+                reads: reads.into_iter().collect(),
+            }
+        }
+    }
+    impl io::Read for MockTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(Ok(bytes)) => {
+                    let n = bytes.len().min(buf.len());
+                    buf[..n].copy_from_slice(&bytes[..n]);
+                    Ok(n)
+                }
+                Some(Err(err)) => Err(err),
+                None => Ok(0),
+            }
+        }
+    }
+    impl io::Write for MockTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Transport for MockTransport {
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn timed_out() -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, "timed out")
+    }
+
+    #[test]
+    fn negotiate_descriptor_treats_immediate_timeout_as_legacy() {
+        let mut transport = MockTransport::new(vec![Err(timed_out())]);
+        let info = negotiate_descriptor(&mut transport, &Config::default()).unwrap();
+        assert_eq!(info.protocol_version, 0);
+    }
+
+    #[test]
+    fn negotiate_descriptor_errors_on_stall_after_partial_descriptor() {
+        //Unlike the legacy case, some bytes arrived before the timeout, so
+        //this must NOT be treated as a legacy device -- doing so would
+        //desync every read that follows.
+        let mut transport = MockTransport::new(vec![Ok(vec![1, 8]), Err(timed_out())]);
+        let result = negotiate_descriptor(&mut transport, &Config::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn negotiate_descriptor_parses_a_full_descriptor() {
+        let mut transport = MockTransport::new(vec![Ok(vec![1, 8, FEATURE_INTERRUPTS, 4])]);
+        let info = negotiate_descriptor(&mut transport, &Config::default()).unwrap();
+        assert_eq!(info.protocol_version, 1);
+        assert_eq!(info.pin_count, 8);
+        assert_eq!(info.max_keys, 4);
+        assert!(info.supports_interrupts());
+        assert!(!info.supports_debounce());
+    }
+
+    #[test]
+    fn negotiate_descriptor_skips_the_probe_when_assume_legacy_device_is_set() {
+        let mut transport = MockTransport::new(vec![]);
+        let mut cfg = Config::default();
+        cfg.assume_legacy_device = true;
+        let info = negotiate_descriptor(&mut transport, &cfg).unwrap();
+        assert_eq!(info.protocol_version, 0);
+    }
+
+    #[test]
+    fn device_info_decodes_feature_bits() {
+        let info = DeviceInfo {
+            protocol_version: 1,
+            pin_count: 1,
+            max_keys: 1,
+            features: FEATURE_INTERRUPTS | FEATURE_DEBOUNCE,
+        };
+        assert!(info.supports_interrupts());
+        assert!(info.supports_debounce());
+
+        let info = DeviceInfo { features: 0, ..info };
+        assert!(!info.supports_interrupts());
+        assert!(!info.supports_debounce());
+    }
+
+    #[test]
+    fn parse_tcp_addr_matches_only_the_tcp_prefix() {
+        assert_eq!(parse_tcp_addr("tcp://192.168.1.5:5331"), Some("192.168.1.5:5331"));
+        assert_eq!(parse_tcp_addr("/dev/ttyUSB0"), None);
+        assert_eq!(parse_tcp_addr(":auto-usb-arduino"), None);
     }
 }
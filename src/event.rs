@@ -3,6 +3,7 @@ use config::{Config};
 use enigo::{self,Enigo,KeyboardControllable};
 use std::cell::RefCell;
 
+#[derive(Copy, Clone)]
 pub enum Event {
     KeyDown(u8),
     KeyUp(u8),
@@ -28,7 +29,7 @@ impl Event {
         fn key_change<F: FnMut(&mut Enigo,enigo::Key)>(cfg: &Config, idx: u8, mut func: F) {
             cfg.key_maps.get(idx as usize).and_then(|keymap| {
                 ENIGO.with(|enigo| for keycode in keymap.keycodes.iter() {
-                    println!("updating physical keycode {}",keycode);
+                    log_trace!("updating physical keycode {}",keycode);
                     func(&mut *enigo.borrow_mut(), enigo::Key::Raw(*keycode))
                 });
                 Some(())
@@ -38,11 +39,11 @@ impl Event {
         //Check event type and act accordingly
         match self {
             Event::KeyDown(idx) => {
-                println!("pressing virtual key {}",idx);
+                log_debug!("pressing virtual key {}",idx);
                 key_change(cfg, idx, Enigo::key_down);
             }
             Event::KeyUp(idx) => {
-                println!("releasing virtual key {}",idx);
+                log_debug!("releasing virtual key {}",idx);
                 key_change(cfg, idx, Enigo::key_up);
             }
         }
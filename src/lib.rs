@@ -5,6 +5,7 @@ extern crate enigo;
 extern crate serde;
 extern crate serde_json as json;
 extern crate subprocess;
+extern crate device_query;
 
 use prelude::*;
 use subprocess::{Exec};
@@ -14,9 +15,13 @@ use std::time::{Duration};
 use config::{Config};
 use connection::{Connection};
 
+#[macro_use]
+mod log;
 mod config;
 mod connection;
 mod event;
+mod monitor;
+mod transport;
 
 mod prelude {
     use std::error::Error;
@@ -40,6 +45,13 @@ mod prelude {
         fn cause(&self) -> Option<&Error> {
             Some(&*self.cause)
         }
+        //`cause`'s `&Error` return type doesn't carry a `'static` bound, so
+        //code that needs to `downcast_ref` while walking the cause chain
+        //(e.g. telling a fatal error apart from a transient one) has to go
+        //through `source` instead, which does.
+        fn source(&self) -> Option<&(Error + 'static)> {
+            Some(&*self.cause)
+        }
     }
     pub trait ResultBoxExt {
         type Mapped;
@@ -72,32 +84,29 @@ mod prelude {
 pub fn run() -> Result<()> {
     //Read configuration files
     let config = Config::create("config.txt");
-    if config.verbose {
-        println!("being verbose");
-    }
+    log::init(config.log_level, config.log_timestamps);
 
     //Run previous command if setup
     if let Some(ref cmd) = config.previous_command {
         let cmd=cmd.replace("{{port}}",&config.resolve_port().unwrap_or_else(|_| config.serial_port.clone()));
-        println!("running setup previous command: {}",cmd);
+        log_info!("running setup previous command: {}",cmd);
         match Exec::shell(&cmd).join() {
             Ok(ref status) if status.success() => {
-                println!("successfully ran previous command");
+                log_info!("successfully ran previous command");
             },
             Ok(status) => {
-                eprintln!("error running previous command, exit status {:?}",status);
+                log_warn!("error running previous command, exit status {:?}",status);
             },
             Err(err) => {
-                eprintln!("failed to run previous command: {}",err);
+                log_error!("failed to run previous command: {}",err);
             },
         }
-        println!();
         thread::sleep(Duration::from_millis(2000));
     }
 
     //Open and handle connection
     let mut conn = Connection::open(&config).chain("failed to open connection")?;
-    println!("handling device events");
+    log_info!("handling device events");
     loop {
         conn.read_event(&config)
             .chain("failed to read event from device")?
@@ -106,6 +115,17 @@ pub fn run() -> Result<()> {
     }
 }
 
+///Open a connection and run the interactive monitor instead of the normal
+///event loop, letting the user watch key states live and rebind keys
+///without hand-editing `config.txt`.
+pub fn run_monitor() -> Result<()> {
+    let config = Config::create("config.txt");
+    log::init(config.log_level, config.log_timestamps);
+
+    let conn = Connection::open(&config).chain("failed to open connection")?;
+    monitor::run(conn, config)
+}
+
 ///Called whether the main function fails or suceeds.
 pub fn finish_off() {
     Exec::shell("pause").join().ok();
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::time::{SystemTime,UNIX_EPOCH};
+
+///How verbose the program's logging should be.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl LogLevel {
+    fn parse(s: &str) -> Option<LogLevel> {
+        Some(match &*s.to_lowercase() {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            "trace" => LogLevel::Trace,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+///Environment variable that overrides the configured log level at startup.
+const LOG_LEVEL_VAR: &str = "SERIAL_KEYBOARD_LOG";
+
+static LEVEL: AtomicUsize = AtomicUsize::new(2); //LogLevel::Info
+static TIMESTAMPS: AtomicUsize = AtomicUsize::new(0);
+
+///Set up the logging subsystem from the config, then let `SERIAL_KEYBOARD_LOG`
+///override the configured level if it names a valid one.
+pub fn init(level: LogLevel, timestamps: bool) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    TIMESTAMPS.store(timestamps as usize, Ordering::Relaxed);
+    if let Ok(val) = ::std::env::var(LOG_LEVEL_VAR) {
+        match LogLevel::parse(&val) {
+            Some(level) => LEVEL.store(level as usize, Ordering::Relaxed),
+            None => eprintln!("unrecognized {} value '{}', ignoring", LOG_LEVEL_VAR, val),
+        }
+    }
+}
+
+fn enabled(level: LogLevel) -> bool {
+    (level as usize) <= LEVEL.load(Ordering::Relaxed)
+}
+
+///Called by the `log_*!` macros, not meant to be used directly.
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    if TIMESTAMPS.load(Ordering::Relaxed) != 0 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let prefix = format!("[{}.{:03}] [{}]", now.as_secs(), now.subsec_millis(), level.name());
+        if level <= LogLevel::Warn {
+            eprintln!("{} {}", prefix, args);
+        } else {
+            println!("{} {}", prefix, args);
+        }
+    } else if level <= LogLevel::Warn {
+        eprintln!("[{}] {}", level.name(), args);
+    } else {
+        println!("[{}] {}", level.name(), args);
+    }
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::LogLevel::Error, format_args!($($arg)*)) };
+}
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::LogLevel::Warn, format_args!($($arg)*)) };
+}
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::LogLevel::Info, format_args!($($arg)*)) };
+}
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::LogLevel::Debug, format_args!($($arg)*)) };
+}
+macro_rules! log_trace {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::LogLevel::Trace, format_args!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_any_case() {
+        assert_eq!(LogLevel::parse("error"), Some(LogLevel::Error));
+        assert_eq!(LogLevel::parse("WARN"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("Debug"), Some(LogLevel::Debug));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_names() {
+        assert_eq!(LogLevel::parse("verbose"), None);
+        assert_eq!(LogLevel::parse(""), None);
+    }
+}
@@ -1,7 +1,13 @@
 extern crate serial_keyboard;
 
 fn main() {
-  if let Err(err) = serial_keyboard::run() {
+  let monitor = ::std::env::args().skip(1).any(|arg| arg == "--monitor");
+  let result = if monitor {
+    serial_keyboard::run_monitor()
+  } else {
+    serial_keyboard::run()
+  };
+  if let Err(err) = result {
     eprintln!("fatal error: {}",err);
   }
   ::std::process::Command::new("pause").output().ok();
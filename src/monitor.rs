@@ -0,0 +1,261 @@
+use prelude::*;
+use config::{Config};
+use connection::{Connection};
+use event::{Event};
+use device_query::{DeviceQuery,DeviceState,Keycode};
+use std::io::{self,BufRead,Write};
+use std::time::{Duration,Instant};
+use std::thread;
+use std::sync::mpsc::{self,Receiver};
+
+const CONFIG_PATH: &str = "config.txt";
+
+///Live up/down state of a configured key, as tracked by the monitor.
+struct KeyState {
+    down: bool,
+    last_event: Option<Instant>,
+}
+
+///A command typed by the user into the monitor.
+enum Command {
+    ///Rebind the key map at this index.
+    Rebind(usize),
+    Quit,
+}
+
+///Run the interactive monitor: render every configured key's live state and
+///let the user rebind one by pressing a key on their own keyboard. Every
+///event is also passed through `Event::consume`, same as the normal event
+///loop, so the device keeps typing while the monitor is open.
+pub fn run(mut conn: Connection, mut cfg: Config) -> Result<()> {
+    let mut states: Vec<KeyState> = cfg
+        .key_maps
+        .iter()
+        .map(|_| KeyState {
+            down: false,
+            last_event: None,
+        })
+        .collect();
+
+    let commands = spawn_command_reader();
+    render(&cfg, &states);
+
+    loop {
+        //Interleave device events with typed commands, instead of blocking
+        //on either exclusively.
+        if let Some(event) = conn.read_event_timeout(Duration::from_millis(100))? {
+            apply_event(&mut states, event);
+            event.consume(&cfg).chain("failed to execute device event")?;
+            render(&cfg, &states);
+        }
+
+        match commands.try_recv() {
+            Ok(Command::Quit) => break,
+            Ok(Command::Rebind(idx)) => {
+                rebind(&mut cfg, idx)?;
+                render(&cfg, &states);
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_event(states: &mut [KeyState], event: Event) {
+    let (idx, down) = match event {
+        Event::KeyDown(idx) => (idx, true),
+        Event::KeyUp(idx) => (idx, false),
+    };
+    if let Some(state) = states.get_mut(idx as usize) {
+        state.down = down;
+        state.last_event = Some(Instant::now());
+    }
+}
+
+///Capture the next physical key the user presses on their keyboard and
+///rewrite the key map at `idx` to map to it, persisting the change to disk.
+fn rebind(cfg: &mut Config, idx: usize) -> Result<()> {
+    let pin = match cfg.key_maps.get(idx) {
+        Some(keymap) => keymap.pin,
+        None => {
+            println!("no key #{} configured", idx);
+            return Ok(());
+        }
+    };
+    println!("rebinding key #{} (pin {}): press the key to map it to...", idx, pin);
+    let keycode = capture_keypress();
+    if let Some(keymap) = cfg.key_maps.get_mut(idx) {
+        keymap.keycodes = vec![keycode];
+    }
+    cfg.save(CONFIG_PATH).chain("failed to save config")?;
+    println!("key #{} (pin {}) now mapped to keycode {}", idx, pin, keycode);
+    Ok(())
+}
+
+///Block until a new key is pressed on the host keyboard, and return the
+///same raw keycode `enigo::Key::Raw` expects for it.
+///
+///`device_query::Keycode`'s own discriminant is just its position in that
+///crate's enum, not a platform keycode, so pressing e.g. `Keycode::A`
+///(discriminant 0) would get replayed by enigo as whatever key its raw
+///code 0 maps to on this platform instead of the letter A. Translate
+///through `keycode_to_raw` instead of casting the enum directly.
+fn capture_keypress() -> u16 {
+    let device_state = DeviceState::new();
+    loop {
+        let held_before = device_state.get_keys();
+        loop {
+            thread::sleep(Duration::from_millis(20));
+            let held_now = device_state.get_keys();
+            let pressed = held_now.iter().find(|key| !held_before.contains(key));
+            if let Some(&key) = pressed {
+                if let Some(code) = keycode_to_raw(key) {
+                    return code;
+                }
+                log_warn!("key {:?} has no known raw keycode mapping, press another key", key);
+                break;
+            }
+        }
+    }
+}
+
+///Translate a `device_query::Keycode` into the Windows virtual-key code
+///that `enigo::Key::Raw` plays back on this platform. Covers the keys
+///`device_query` can report; an unmapped key returns `None`.
+fn keycode_to_raw(key: Keycode) -> Option<u16> {
+    Some(match key {
+        Keycode::Key0 => 0x30, Keycode::Key1 => 0x31, Keycode::Key2 => 0x32,
+        Keycode::Key3 => 0x33, Keycode::Key4 => 0x34, Keycode::Key5 => 0x35,
+        Keycode::Key6 => 0x36, Keycode::Key7 => 0x37, Keycode::Key8 => 0x38,
+        Keycode::Key9 => 0x39,
+        Keycode::A => 0x41, Keycode::B => 0x42, Keycode::C => 0x43,
+        Keycode::D => 0x44, Keycode::E => 0x45, Keycode::F => 0x46,
+        Keycode::G => 0x47, Keycode::H => 0x48, Keycode::I => 0x49,
+        Keycode::J => 0x4A, Keycode::K => 0x4B, Keycode::L => 0x4C,
+        Keycode::M => 0x4D, Keycode::N => 0x4E, Keycode::O => 0x4F,
+        Keycode::P => 0x50, Keycode::Q => 0x51, Keycode::R => 0x52,
+        Keycode::S => 0x53, Keycode::T => 0x54, Keycode::U => 0x55,
+        Keycode::V => 0x56, Keycode::W => 0x57, Keycode::X => 0x58,
+        Keycode::Y => 0x59, Keycode::Z => 0x5A,
+        Keycode::F1 => 0x70, Keycode::F2 => 0x71, Keycode::F3 => 0x72,
+        Keycode::F4 => 0x73, Keycode::F5 => 0x74, Keycode::F6 => 0x75,
+        Keycode::F7 => 0x76, Keycode::F8 => 0x77, Keycode::F9 => 0x78,
+        Keycode::F10 => 0x79, Keycode::F11 => 0x7A, Keycode::F12 => 0x7B,
+        Keycode::Escape => 0x1B,
+        Keycode::Space => 0x20,
+        Keycode::Enter => 0x0D,
+        Keycode::Backspace => 0x08,
+        Keycode::Tab => 0x09,
+        Keycode::CapsLock => 0x14,
+        Keycode::Home => 0x24,
+        Keycode::End => 0x23,
+        Keycode::PageUp => 0x21,
+        Keycode::PageDown => 0x22,
+        Keycode::Insert => 0x2D,
+        Keycode::Delete => 0x2E,
+        Keycode::Up => 0x26,
+        Keycode::Down => 0x28,
+        Keycode::Left => 0x25,
+        Keycode::Right => 0x27,
+        Keycode::LControl => 0xA2, Keycode::RControl => 0xA3,
+        Keycode::LShift => 0xA0, Keycode::RShift => 0xA1,
+        Keycode::LAlt => 0xA4, Keycode::RAlt => 0xA5,
+        Keycode::Meta => 0x5B,
+        Keycode::Numpad0 => 0x60, Keycode::Numpad1 => 0x61, Keycode::Numpad2 => 0x62,
+        Keycode::Numpad3 => 0x63, Keycode::Numpad4 => 0x64, Keycode::Numpad5 => 0x65,
+        Keycode::Numpad6 => 0x66, Keycode::Numpad7 => 0x67, Keycode::Numpad8 => 0x68,
+        Keycode::Numpad9 => 0x69,
+        Keycode::NumpadAdd => 0x6B, Keycode::NumpadSubtract => 0x6D,
+        Keycode::NumpadMultiply => 0x6A, Keycode::NumpadDivide => 0x6F,
+        Keycode::Grave => 0xC0,
+        Keycode::Minus => 0xBD,
+        Keycode::Equal => 0xBB,
+        Keycode::LeftBracket => 0xDB,
+        Keycode::RightBracket => 0xDD,
+        Keycode::BackSlash => 0xDC,
+        Keycode::Semicolon => 0xBA,
+        Keycode::Apostrophe => 0xDE,
+        Keycode::Comma => 0xBC,
+        Keycode::Dot => 0xBE,
+        Keycode::Slash => 0xBF,
+        _ => return None,
+    })
+}
+
+///Spawn a thread that parses lines typed on stdin into `Command`s: a number
+///to rebind that key, or `q` to quit.
+fn spawn_command_reader() -> Receiver<Command> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let line = line.trim();
+            if line.eq_ignore_ascii_case("q") {
+                tx.send(Command::Quit).ok();
+                break;
+            } else if let Ok(idx) = line.parse::<usize>() {
+                if tx.send(Command::Rebind(idx)).is_err() {
+                    break;
+                }
+            } else {
+                log_warn!("unrecognized monitor command '{}'", line);
+            }
+        }
+    });
+    rx
+}
+
+fn render(cfg: &Config, states: &[KeyState]) {
+    //Clear the screen and move the cursor to the top.
+    print!("\x1B[2J\x1B[H");
+    println!("serial-keyboard monitor -- type a key's # to rebind it, 'q' to quit");
+    println!("{:>3} {:>4} {:>6} {:>12} {}", "#", "pin", "state", "last event", "keycodes");
+    for (idx, (keymap, state)) in cfg.key_maps.iter().zip(states.iter()).enumerate() {
+        let since = match state.last_event {
+            Some(at) => format!("{}ms ago", at.elapsed().as_millis()),
+            None => "-".into(),
+        };
+        println!(
+            "{:>3} {:>4} {:>6} {:>12} {:?}",
+            idx,
+            keymap.pin,
+            if state.down { "down" } else { "up" },
+            since,
+            keymap.keycodes,
+        );
+    }
+    io::stdout().flush().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keycode_to_raw_maps_letters_and_digits_to_windows_vk_codes() {
+        assert_eq!(keycode_to_raw(Keycode::A), Some(0x41));
+        assert_eq!(keycode_to_raw(Keycode::Z), Some(0x5A));
+        assert_eq!(keycode_to_raw(Keycode::Key0), Some(0x30));
+        assert_eq!(keycode_to_raw(Keycode::Key9), Some(0x39));
+    }
+
+    #[test]
+    fn keycode_to_raw_maps_common_control_keys() {
+        assert_eq!(keycode_to_raw(Keycode::Enter), Some(0x0D));
+        assert_eq!(keycode_to_raw(Keycode::Space), Some(0x20));
+        assert_eq!(keycode_to_raw(Keycode::Escape), Some(0x1B));
+    }
+
+    #[test]
+    fn keycode_to_raw_maps_numpad_and_punctuation_keys() {
+        assert_eq!(keycode_to_raw(Keycode::Numpad5), Some(0x65));
+        assert_eq!(keycode_to_raw(Keycode::Semicolon), Some(0xBA));
+        assert_eq!(keycode_to_raw(Keycode::Slash), Some(0xBF));
+    }
+}
@@ -0,0 +1,59 @@
+use prelude::*;
+use serialport::SerialPort;
+use std::io::{self,Read,Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+///A byte stream carrying the serial-keyboard protocol, whether that's a
+///physical serial port or a TCP socket bridging one (e.g. a ser2net-style
+///gateway).
+pub trait Transport: Read + Write {
+    ///Set the read timeout, or disable it by passing a zero duration.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
+}
+
+impl Transport for Box<SerialPort> {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        Ok(SerialPort::set_timeout(&mut **self, timeout)?)
+    }
+}
+
+///Connects to a device bridged over TCP, as opposed to a local serial port.
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+impl TcpTransport {
+    ///Connect to a `host:port` address.
+    pub fn connect(addr: &str, timeout_ms: u64) -> Result<TcpTransport> {
+        let stream = TcpStream::connect(addr).chain("failed to connect to tcp device")?;
+        stream.set_nodelay(true).ok();
+        let mut transport = TcpTransport { stream };
+        transport.set_timeout(Duration::from_millis(timeout_ms))?;
+        Ok(transport)
+    }
+}
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+impl Transport for TcpTransport {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let timeout = if timeout == Duration::from_millis(0) {
+            None
+        } else {
+            Some(timeout)
+        };
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.set_write_timeout(timeout)?;
+        Ok(())
+    }
+}